@@ -4,10 +4,15 @@
 //! 1. Launches the Go `imf` binary as a sidecar with `imf gui`
 //! 2. Sets IMF_NO_BROWSER=1 so the Go binary doesn't open a browser
 //! 3. Detects the port from sidecar stdout
-//! 4. Handles macOS file association via RunEvent::Opened (Apple Events)
-//! 5. Creates a native Tauri webview window pointing at the local HTTP server
-//! 6. Kills the sidecar on window close
+//! 4. Handles macOS file association via RunEvent::Opened (Apple Events), and
+//!    Windows/Linux file association via argv parsing + a single-instance guard
+//! 5. Creates a native Tauri webview window pointing at the local HTTP server,
+//!    opening a separate window per opened file, all sharing one sidecar
+//! 6. Kills the sidecar once the last window closes
+//! 7. Isolates IPC: the sidecar's HTTP origin is treated as remote and can't
+//!    invoke Tauri commands unless explicitly opted in
 
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
 use std::process::{Child, Command, Stdio};
 use std::sync::Mutex;
@@ -16,6 +21,19 @@ use tauri::Manager;
 struct SidecarState {
     child: Mutex<Option<Child>>,
     port: Mutex<u16>,
+    /// Commands the sidecar's HTTP origin is allowed to invoke despite being
+    /// remote-served content. Empty by default — opt in per command.
+    allowed_remote_commands: Mutex<HashSet<String>>,
+    /// Number of open windows sharing this sidecar. The sidecar is only
+    /// killed once this reaches zero.
+    window_count: Mutex<usize>,
+    /// Maps a canonicalized opened-file path to the label of the window
+    /// showing it. Keyed by full path rather than filename so that two
+    /// distinct files with the same name (e.g. `a/x.imf` vs `b/x.imf`) never
+    /// collide onto the same window.
+    window_labels: Mutex<HashMap<String, String>>,
+    /// Counter used to mint fresh, collision-free window labels.
+    next_window_id: Mutex<u64>,
 }
 
 fn sidecar_path(app: &tauri::AppHandle) -> std::path::PathBuf {
@@ -42,11 +60,92 @@ fn sidecar_path(app: &tauri::AppHandle) -> std::path::PathBuf {
     std::path::PathBuf::from(binary_name)
 }
 
+/// Detects whether we're running inside an AppImage, Flatpak, or Snap
+/// sandbox, returning the bundle's root directory if so.
+///
+/// `APPDIR` is what actually matters for AppImage: it's the extracted
+/// runtime's mount point (e.g. `/tmp/.mount_XXXX`) that leaked `PATH`/
+/// `LD_LIBRARY_PATH` entries point into. `APPIMAGE` itself just names the
+/// `.AppImage` file the user launched — its parent directory (wherever that
+/// file happens to live) is unrelated to the mount and was never a usable
+/// fallback, so it isn't treated as one here. The AppImage runtime always
+/// sets `APPDIR` alongside `APPIMAGE`.
+fn detect_sandbox_root() -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os("APPDIR") {
+        return Some(std::path::PathBuf::from(dir));
+    }
+    if let Some(snap) = std::env::var_os("SNAP") {
+        return Some(std::path::PathBuf::from(snap));
+    }
+    if std::path::Path::new("/.flatpak-info").exists() {
+        return Some(
+            std::env::var_os("FLATPAK_DEST")
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| std::path::PathBuf::from("/app")),
+        );
+    }
+    None
+}
+
+/// Strips entries rooted under `bundle_root` from a colon-separated
+/// PATH-style variable, de-duplicating while keeping the first (highest
+/// priority) occurrence of each remaining entry. Returns `None` when the
+/// cleaned value would be empty, signaling the variable should be unset.
+fn sanitize_path_var(value: &str, bundle_root: &std::path::Path) -> Option<String> {
+    let mut seen = HashSet::new();
+    let cleaned: Vec<&str> = value
+        .split(':')
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| !std::path::Path::new(entry).starts_with(bundle_root))
+        .filter(|entry| seen.insert(*entry))
+        .collect();
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.join(":"))
+    }
+}
+
+/// Computes the PATH-style environment variables to set or unset on the
+/// sidecar `Command` so bundle-injected entries (AppImage/Flatpak/Snap)
+/// don't leak into the Go binary. Our own process environment is untouched —
+/// this only decides what to layer on top of the child's inherited env.
+fn sanitized_sidecar_env() -> (Vec<(String, String)>, Vec<String>) {
+    const PATH_STYLE_VARS: &[&str] = &[
+        "PATH",
+        "LD_LIBRARY_PATH",
+        "GST_PLUGIN_PATH",
+        "PYTHONPATH",
+        "XDG_DATA_DIRS",
+        "XDG_CONFIG_DIRS",
+    ];
+    let mut to_set = Vec::new();
+    let mut to_remove = Vec::new();
+    if let Some(bundle_root) = detect_sandbox_root() {
+        for &name in PATH_STYLE_VARS {
+            if let Ok(value) = std::env::var(name) {
+                match sanitize_path_var(&value, &bundle_root) {
+                    Some(cleaned) => to_set.push((name.to_string(), cleaned)),
+                    None => to_remove.push(name.to_string()),
+                }
+            }
+        }
+    }
+    (to_set, to_remove)
+}
+
 fn launch_sidecar(app: &tauri::AppHandle) -> Result<(Child, u16), String> {
     let binary = sidecar_path(app);
-    let mut child = Command::new(&binary)
-        .arg("gui")
-        .env("IMF_NO_BROWSER", "1")
+    let (env_to_set, env_to_remove) = sanitized_sidecar_env();
+    let mut command = Command::new(&binary);
+    command.arg("gui").env("IMF_NO_BROWSER", "1");
+    for (key, value) in env_to_set {
+        command.env(key, value);
+    }
+    for key in env_to_remove {
+        command.env_remove(key);
+    }
+    let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::inherit())
         .spawn()
@@ -82,7 +181,24 @@ fn urlencod(s: &str) -> String {
     r
 }
 
-/// Copy .imf file to sidecar workdir (Desktop). Returns filename.
+/// Recursively copy a directory tree into `dest`, creating directories as needed.
+fn copy_dir_recursive(src: &std::path::Path, dest: &std::path::Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let entry_path = entry.path();
+        let dest_path = dest.join(entry.file_name());
+        if entry_path.is_dir() {
+            copy_dir_recursive(&entry_path, &dest_path)?;
+        } else {
+            std::fs::copy(&entry_path, &dest_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy a .imf file, or a directory bundle, to the sidecar workdir (Desktop).
+/// Returns the file/bundle name.
 fn copy_to_workdir(file_path: &str) -> Option<String> {
     let path = std::path::Path::new(file_path);
     let file_name = path.file_name()?.to_string_lossy().to_string();
@@ -95,7 +211,15 @@ fn copy_to_workdir(file_path: &str) -> Option<String> {
             };
         let dest = dest_dir.join(&file_name);
         if path.canonicalize().ok() != dest.canonicalize().ok() {
-            let _ = std::fs::copy(file_path, &dest);
+            if path.is_dir() {
+                // Wipe any previous copy of this bundle first — otherwise a
+                // dest file dropped from the new source tree would survive
+                // as stale leftover content alongside the fresh copy.
+                let _ = std::fs::remove_dir_all(&dest);
+                let _ = copy_dir_recursive(path, &dest);
+            } else {
+                let _ = std::fs::copy(file_path, &dest);
+            }
         }
     }
     Some(file_name)
@@ -111,14 +235,177 @@ fn imf_path_from_url(url: &tauri::Url) -> Option<String> {
     path_str.filter(|p| p.ends_with(".imf"))
 }
 
+/// Same `.imf` suffix check used by `imf_path_from_url`, applied to dropped
+/// paths. Matches both single files and directory bundles ending in `.imf`.
+fn is_imf_path(path: &std::path::Path) -> bool {
+    path.to_string_lossy().ends_with(".imf")
+}
+
+/// Mints a fresh, collision-free window label from `SidecarState::next_window_id`.
+/// Returns `None` if the lock is poisoned, same as every other lock in this file.
+fn next_window_label(state: &SidecarState) -> Option<String> {
+    let mut id = state.next_window_id.lock().ok()?;
+    *id += 1;
+    Some(format!("file-{}", id))
+}
+
+/// Builds the webview window shared by every opened file: same chrome,
+/// size, and navigation policy as the original single "main" window.
+fn build_window(
+    handle: &tauri::AppHandle,
+    label: &str,
+    url: String,
+) -> tauri::Result<tauri::WebviewWindow> {
+    tauri::WebviewWindowBuilder::new(handle, label, tauri::WebviewUrl::External(url.parse().unwrap()))
+        .title("IMF Viewer")
+        .inner_size(1100.0, 750.0)
+        .min_inner_size(800.0, 500.0)
+        .center()
+        .on_navigation(|url| {
+            url.host_str() == Some("127.0.0.1")
+                || url.host_str() == Some("localhost")
+                || url.scheme() == "tauri"
+                || url.scheme() == "about"
+        })
+        .build()
+}
+
+/// Builds a new window and, on success, counts it against `SidecarState::window_count`
+/// so the sidecar is only killed once the last window is destroyed. Errors
+/// are logged rather than propagated, for every caller: the event handlers
+/// (drag-drop, Opened, single-instance forwarding) and setup()'s loop over
+/// files queued before startup.
+fn spawn_window(app_handle: &tauri::AppHandle, label: &str, url: String) {
+    match build_window(app_handle, label, url) {
+        Ok(_) => {
+            if let Some(state) = app_handle.try_state::<SidecarState>() {
+                if let Ok(mut count) = state.window_count.lock() {
+                    *count += 1;
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to open window {:?}: {}", label, e),
+    }
+}
+
+/// Opens `path` in its own window, copying it into the sidecar workdir
+/// first. Focuses the existing window instead of duplicating it if the same
+/// file is already open. Used by the macOS `Opened` path, window file-drops,
+/// argv parsing, and the single-instance forwarding callback.
+fn open_in_window(app_handle: &tauri::AppHandle, path: &str) {
+    let Some(state) = app_handle.try_state::<SidecarState>() else { return };
+    let Ok(port) = state.port.lock() else { return };
+
+    // Key on the canonicalized path, not the filename,
+    // so distinct files can never be mistaken for the same open window.
+    let key = std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string());
+
+    if let Some(label) = state.window_labels.lock().ok().and_then(|m| m.get(&key).cloned()) {
+        if let Some(window) = app_handle.get_webview_window(&label) {
+            let _ = window.set_focus();
+            return;
+        }
+    }
+
+    let Some(file_name) = copy_to_workdir(path) else { return };
+    let Some(label) = next_window_label(&state) else { return };
+    if let Ok(mut labels) = state.window_labels.lock() {
+        labels.insert(key, label.clone());
+    }
+    let url = format!("http://127.0.0.1:{}/?open={}", *port, urlencod(&file_name));
+    spawn_window(app_handle, &label, url);
+}
+
+/// Tauri's own local asset protocols — never the sidecar's `http://127.0.0.1`
+/// origin. Pages served from one of these may freely invoke commands.
+fn is_trusted_local_origin(origin: &str) -> bool {
+    origin.starts_with("tauri://") || origin.starts_with("https://tauri.localhost")
+}
+
+/// Blocks IPC from the sidecar's remote HTTP origin unless the invoked
+/// command has been explicitly opted in via `SidecarState::allowed_remote_commands`.
+///
+/// Reads the origin off the invoking webview's current URL
+/// (`invoke.message.webview().url()`), since each opened file gets its own
+/// webview and each can sit on a different origin (trusted local window vs.
+/// sidecar-served window).
+/// NOTE: for production hardening prefer Tauri's built-in
+/// `app.security.dangerousRemoteDomainIpcAccess` allowlist in
+/// `tauri.conf.json`, which scopes this per-window/per-command without
+/// hand-rolled origin checks; this function exists as the code-level
+/// complement since this repo snapshot has no `tauri.conf.json` to configure.
+fn ipc_allowed(invoke: &tauri::ipc::Invoke, state: &SidecarState) -> bool {
+    let origin = invoke
+        .message
+        .webview()
+        .url()
+        .map(|url| url.to_string())
+        .unwrap_or_default();
+    if is_trusted_local_origin(&origin) {
+        return true;
+    }
+    state
+        .allowed_remote_commands
+        .lock()
+        .map(|set| set.contains(invoke.message.command()))
+        .unwrap_or(false)
+}
+
+/// Scan a set of CLI args (as passed to a freshly launched process, or
+/// forwarded from a second instance) for `.imf` paths, using the same
+/// suffix check as `is_imf_path`.
+fn imf_paths_from_args<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    args.into_iter()
+        .filter(|arg| is_imf_path(std::path::Path::new(arg)))
+        .collect()
+}
+
 fn main() {
-    // Shared state to capture file paths from early RunEvent::Opened events.
+    // Shared state to capture file paths from early RunEvent::Opened events,
+    // and on Windows/Linux from `.imf` paths found in argv at startup.
     // On macOS, Opened fires BEFORE setup() when double-clicking a file to launch.
-    let pending = std::sync::Arc::new(Mutex::new(Option::<String>::None));
+    let pending = std::sync::Arc::new(Mutex::new(Vec::<String>::new()));
     let pending_for_setup = pending.clone();
+    let pending_for_single_instance = pending.clone();
+
+    // Windows/Linux file association launches us with the path(s) as CLI args.
+    if let Ok(mut p) = pending.lock() {
+        p.extend(imf_paths_from_args(std::env::args().skip(1)));
+    }
+
+    // No Tauri commands are exposed yet, but the invoke handler still runs
+    // the IPC-isolation check below for every command added in the future.
+    let base_invoke_handler = tauri::generate_handler![];
 
     let app = tauri::Builder::default()
+        .plugin(tauri_plugin_single_instance::init(move |app, argv, _cwd| {
+            // A second launch forwards its argv here; reuse the running
+            // instance (and its sidecar), opening a window per file.
+            for path in imf_paths_from_args(argv.into_iter().skip(1)) {
+                if app.try_state::<SidecarState>().is_some() {
+                    open_in_window(app, &path);
+                } else if let Ok(mut p) = pending_for_single_instance.lock() {
+                    // setup() hasn't finished yet — store for it to pick up,
+                    // same as the macOS Opened-before-setup race below.
+                    p.push(path);
+                }
+            }
+        }))
         .plugin(tauri_plugin_shell::init())
+        .invoke_handler(move |invoke| {
+            let allowed = invoke
+                .message
+                .webview()
+                .try_state::<SidecarState>()
+                .map(|state| ipc_allowed(&invoke, &state))
+                .unwrap_or(false);
+            if !allowed {
+                return false;
+            }
+            base_invoke_handler(invoke)
+        })
         .setup(move |app| {
             let handle = app.handle().clone();
             let (child, port) = launch_sidecar(&handle)
@@ -127,78 +414,135 @@ fn main() {
             app.manage(SidecarState {
                 child: Mutex::new(Some(child)),
                 port: Mutex::new(port),
+                allowed_remote_commands: Mutex::new(HashSet::new()),
+                window_count: Mutex::new(0),
+                window_labels: Mutex::new(HashMap::new()),
+                next_window_id: Mutex::new(0),
             });
 
-            // Check if a file path was stored by an early Opened event
-            let pending_file = pending_for_setup.lock().ok().and_then(|mut p| p.take());
-            let file_name = pending_file.as_ref().and_then(|path| copy_to_workdir(path));
-
-            let url = match file_name {
-                Some(ref name) => format!("http://127.0.0.1:{}/?open={}", port, urlencod(name)),
-                None => format!("http://127.0.0.1:{}", port),
-            };
-
-            let _window = tauri::WebviewWindowBuilder::new(
-                &handle,
-                "main",
-                tauri::WebviewUrl::External(url.parse().unwrap()),
-            )
-            .title("IMF Viewer")
-            .inner_size(1100.0, 750.0)
-            .min_inner_size(800.0, 500.0)
-            .center()
-            .on_navigation(|url| {
-                url.host_str() == Some("127.0.0.1")
-                    || url.host_str() == Some("localhost")
-                    || url.scheme() == "tauri"
-                    || url.scheme() == "about"
-            })
-            .build()?;
+            // Check for file paths stored by an early Opened event or argv
+            let pending_files = pending_for_setup.lock().map(|mut p| std::mem::take(&mut *p)).unwrap_or_default();
+            if pending_files.is_empty() {
+                // Unlike per-file windows opened later, a failure to create
+                // this first window leaves the app with nothing to show and
+                // no way to ever trigger sidecar cleanup — propagate it like
+                // the original single-window `.build()?` did.
+                build_window(&handle, "main", format!("http://127.0.0.1:{}", port))?;
+                if let Ok(mut count) = handle.state::<SidecarState>().window_count.lock() {
+                    *count += 1;
+                }
+            } else {
+                for path in pending_files {
+                    open_in_window(&handle, &path);
+                }
+            }
 
             Ok(())
         })
-        .on_window_event(|window, event| {
-            if let tauri::WindowEvent::Destroyed = event {
+        .on_window_event(|window, event| match event {
+            tauri::WindowEvent::Destroyed => {
                 if let Some(state) = window.try_state::<SidecarState>() {
-                    if let Ok(mut child) = state.child.lock() {
-                        if let Some(ref mut c) = *child {
-                            let _ = c.kill();
+                    if let Ok(mut labels) = state.window_labels.lock() {
+                        labels.retain(|_, label| label != window.label());
+                    }
+                    let remaining = state
+                        .window_count
+                        .lock()
+                        .map(|mut count| {
+                            *count = count.saturating_sub(1);
+                            *count
+                        })
+                        .unwrap_or(0);
+                    if remaining == 0 {
+                        if let Ok(mut child) = state.child.lock() {
+                            if let Some(ref mut c) = *child {
+                                let _ = c.kill();
+                            }
                         }
                     }
                 }
             }
+            tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) => {
+                for path in paths {
+                    if is_imf_path(path) {
+                        open_in_window(&window.app_handle().clone(), &path.to_string_lossy());
+                    }
+                }
+            }
+            _ => {}
         })
         .build(tauri::generate_context!())
         .expect("error building IMF Viewer");
 
     // Handle macOS file association via RunEvent::Opened.
     // When user double-clicks an .imf file, macOS sends an Apple Event
-    // which Tauri delivers as RunEvent::Opened with file:// URLs.
+    // which Tauri delivers as RunEvent::Opened with file:// URLs. Each path
+    // opens (or focuses) its own window.
     app.run(move |app_handle, event| {
         if let tauri::RunEvent::Opened { urls } = &event {
             for url in urls {
                 if let Some(path) = imf_path_from_url(url) {
-                    // If sidecar is ready, navigate the existing window
-                    if let Some(state) = app_handle.try_state::<SidecarState>() {
-                        if let Ok(port) = state.port.lock() {
-                            if let Some(file_name) = copy_to_workdir(&path) {
-                                let nav_url = format!(
-                                    "http://127.0.0.1:{}/?open={}",
-                                    *port, urlencod(&file_name)
-                                );
-                                if let Some(window) = app_handle.get_webview_window("main") {
-                                    let _ = window.navigate(nav_url.parse().unwrap());
-                                }
-                            }
-                            return;
-                        }
+                    // If the sidecar is ready, open a window for it directly
+                    if app_handle.try_state::<SidecarState>().is_some() {
+                        open_in_window(app_handle, &path);
+                        continue;
                     }
                     // Sidecar not ready yet — store for setup() to pick up
                     if let Ok(mut p) = pending.lock() {
-                        *p = Some(path);
+                        p.push(path);
                     }
                 }
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusts_tauri_asset_protocol() {
+        assert!(is_trusted_local_origin("tauri://localhost/index.html"));
+    }
+
+    #[test]
+    fn trusts_windows_tauri_localhost() {
+        assert!(is_trusted_local_origin("https://tauri.localhost/index.html"));
+    }
+
+    #[test]
+    fn distrusts_sidecar_http_origin() {
+        assert!(!is_trusted_local_origin("http://127.0.0.1:5173/"));
+        assert!(!is_trusted_local_origin("http://localhost:5173/"));
+    }
+
+    #[test]
+    fn sanitize_path_var_strips_entries_under_bundle_root() {
+        let bundle_root = std::path::Path::new("/app");
+        let cleaned = sanitize_path_var("/app/bin:/usr/bin:/app/lib", bundle_root).unwrap();
+        assert_eq!(cleaned, "/usr/bin");
+    }
+
+    #[test]
+    fn sanitize_path_var_only_matches_whole_path_components() {
+        // `/application/bin` shares a string prefix with `/app` but isn't
+        // actually under it, so it must survive.
+        let bundle_root = std::path::Path::new("/app");
+        let cleaned = sanitize_path_var("/application/bin:/app/bin", bundle_root).unwrap();
+        assert_eq!(cleaned, "/application/bin");
+    }
+
+    #[test]
+    fn sanitize_path_var_dedups_keeping_first_occurrence() {
+        let bundle_root = std::path::Path::new("/app");
+        let cleaned = sanitize_path_var("/usr/bin:/usr/local/bin:/usr/bin", bundle_root).unwrap();
+        assert_eq!(cleaned, "/usr/bin:/usr/local/bin");
+    }
+
+    #[test]
+    fn sanitize_path_var_returns_none_when_fully_stripped() {
+        let bundle_root = std::path::Path::new("/app");
+        assert_eq!(sanitize_path_var("/app/bin:/app/lib", bundle_root), None);
+    }
+}